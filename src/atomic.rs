@@ -0,0 +1,123 @@
+// Implements atomic, multi-key writes with optimistic version checks,
+// modeled on Deno KV's atomic operations.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    kvrecord::{KVValue, KVWriteValue},
+    wal::{WAL, OP_DELETE, OP_SET},
+    ToyKVError,
+};
+
+enum AtomicOp {
+    Set(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+}
+
+/// A builder for a batch of mutations that are checked and applied as
+/// one atomic unit: `db.atomic().check(k, seq).set(k, v).commit()`.
+///
+/// A commit only goes ahead if every queued `check` matches the key's
+/// current seq (the WAL seq it was last written at); otherwise nothing
+/// in the batch is applied. Once the checks pass, every queued `set`/
+/// `delete` is written to the WAL as a single contiguous group (see
+/// `WAL::write_batch`) so a crash can never apply half of a batch.
+///
+/// `check` only consults the live memtable (there is no on-disk,
+/// flushed state to fall back to yet in this codebase), so it is only
+/// meaningful against a key that is still resident there. If a future
+/// flush path ever evicts written keys from the memtable, `check`
+/// against an evicted key will need to fall back to whatever tracks
+/// its last-written seq after eviction, or it will wrongly see seq 0.
+pub struct Atomic<'a> {
+    wal: &'a mut WAL,
+    memtable: &'a mut BTreeMap<Vec<u8>, KVValue>,
+    checks: Vec<(Vec<u8>, u32)>,
+    ops: Vec<AtomicOp>,
+}
+
+impl<'a> Atomic<'a> {
+    /// Starts building a batch over the given WAL and memtable. Expected
+    /// to be constructed by `ToyKV::atomic()`, borrowing its own fields.
+    pub(crate) fn new(wal: &'a mut WAL, memtable: &'a mut BTreeMap<Vec<u8>, KVValue>) -> Self {
+        Atomic {
+            wal,
+            memtable,
+            checks: Vec::new(),
+            ops: Vec::new(),
+        }
+    }
+
+    /// Fails the whole commit unless `key`'s last-write seq is exactly
+    /// `expected_seq`. A key that has never been written has seq 0.
+    ///
+    /// The seq is read from the live memtable, so this is only accurate
+    /// for a key that hasn't been evicted from it (see the struct docs).
+    pub fn check(mut self, key: &[u8], expected_seq: u32) -> Self {
+        self.checks.push((key.to_vec(), expected_seq));
+        self
+    }
+
+    /// Queues a set of `key` to `value` as part of the batch.
+    pub fn set(mut self, key: Vec<u8>, value: Vec<u8>) -> Self {
+        self.ops.push(AtomicOp::Set(key, value));
+        self
+    }
+
+    /// Queues a delete of `key` as part of the batch.
+    pub fn delete(mut self, key: Vec<u8>) -> Self {
+        self.ops.push(AtomicOp::Delete(key));
+        self
+    }
+
+    /// Validates every queued `check` against the current memtable. If
+    /// they all pass, writes every queued mutation to the WAL as a
+    /// single atomic group and applies it to the memtable, returning
+    /// `Ok(true)`. If any check fails, nothing is written and this
+    /// returns `Ok(false)`.
+    pub fn commit(self) -> Result<bool, ToyKVError> {
+        for (key, expected_seq) in &self.checks {
+            let actual_seq = self
+                .memtable
+                .get(key.as_slice())
+                .map(|v| v.seq())
+                .unwrap_or(0);
+            if actual_seq != *expected_seq {
+                return Ok(false);
+            }
+        }
+
+        if self.ops.is_empty() {
+            return Ok(true);
+        }
+
+        let empty: Vec<u8> = Vec::new();
+        let wal_ops: Vec<(u8, &[u8], KVWriteValue)> = self
+            .ops
+            .iter()
+            .map(|op| match op {
+                AtomicOp::Set(key, value) => (OP_SET, key.as_slice(), KVWriteValue { value }),
+                AtomicOp::Delete(key) => (
+                    OP_DELETE,
+                    key.as_slice(),
+                    KVWriteValue { value: &empty },
+                ),
+            })
+            .collect();
+
+        let seqs = self.wal.write_batch(&wal_ops)?;
+
+        for (op, seq) in self.ops.into_iter().zip(seqs) {
+            match op {
+                AtomicOp::Set(key, value) => {
+                    self.memtable.insert(key, KVValue::Set { value, seq });
+                }
+                AtomicOp::Delete(key) => {
+                    self.memtable.insert(key, KVValue::Deleted { seq });
+                }
+            }
+        }
+
+        Ok(true)
+    }
+}
@@ -0,0 +1,33 @@
+// Naming and discovery helpers for WAL segment files. Kept separate
+// from wal.rs's record-level logic since this is pure filesystem/path
+// bookkeeping.
+
+use std::{fs, io, path::Path, path::PathBuf};
+
+/// A segment is rolled once it holds at least this many bytes.
+pub(crate) const SEGMENT_SIZE_THRESHOLD: u64 = 4 * 1024 * 1024;
+
+/// The path of the segment with the given id, e.g. `db.wal.000001`.
+pub(crate) fn segment_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("db.wal.{:06}", id))
+}
+
+/// Lists the ids of every WAL segment already present in `dir`, in
+/// ascending order. Used by `WAL::replay` to find where a previous run
+/// left off; empty if this is a brand new database directory.
+pub(crate) fn list_segment_ids(dir: &Path) -> io::Result<Vec<u64>> {
+    let mut ids = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let Some(name) = entry.file_name().to_str().map(str::to_owned) else {
+            continue;
+        };
+        if let Some(suffix) = name.strip_prefix("db.wal.") {
+            if let Ok(id) = suffix.parse::<u64>() {
+                ids.push(id);
+            }
+        }
+    }
+    ids.sort_unstable();
+    Ok(ids)
+}
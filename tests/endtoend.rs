@@ -1,3 +1,6 @@
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+
 use toykv::ToyKVError;
 
 #[test]
@@ -8,10 +11,7 @@ fn insert_and_readback() -> Result<(), ToyKVError> {
     let k = "foo".to_string();
     let v = "the rain in spain falls mainly on the plain".to_string();
 
-    match db.set(k.clone().into_bytes(), v.clone().into_bytes()) {
-        Ok(it) => it,
-        Err(err) => return Err(err),
-    };
+    db.set(k.clone().into_bytes(), v.clone().into_bytes())?;
     let got = db.get(k.as_bytes())?;
 
     assert_eq!(
@@ -24,3 +24,320 @@ fn insert_and_readback() -> Result<(), ToyKVError> {
 
     Ok(())
 }
+
+/// A crash mid-append leaves a torn record at the end of the WAL. Replay
+/// should recover every record before it and silently drop the torn one,
+/// rather than failing to open the database.
+#[test]
+fn recovers_from_truncated_tail() -> Result<(), ToyKVError> {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let mut db = toykv::open(tmp_dir.path());
+
+    db.set(b"k1".to_vec(), b"v1".to_vec())?;
+    db.set(b"k2".to_vec(), b"v2".to_vec())?;
+    let before_k3_len = std::fs::metadata(tmp_dir.path().join("db.wal.000001"))
+        .unwrap()
+        .len();
+    db.set(b"k3".to_vec(), b"v3".to_vec())?;
+    db.shutdown();
+
+    // Chop off the last few bytes of k3's record, as if the process died
+    // mid-write.
+    let wal_path = tmp_dir.path().join("db.wal.000001");
+    let full_len = std::fs::metadata(&wal_path).unwrap().len();
+    let f = OpenOptions::new().write(true).open(&wal_path).unwrap();
+    f.set_len(full_len - 3).unwrap();
+    assert!(before_k3_len < full_len - 3, "test wrote too little to tear");
+
+    let mut db = toykv::open(tmp_dir.path());
+    assert_eq!(db.get(b"k1")?.unwrap(), b"v1");
+    assert_eq!(db.get(b"k2")?.unwrap(), b"v2");
+    assert_eq!(db.get(b"k3")?, None, "torn record should not survive replay");
+    db.shutdown();
+
+    Ok(())
+}
+
+/// Bit-rot in the last record should be detected by the checksum and
+/// treated the same as a torn write: everything before it survives.
+#[test]
+fn recovers_from_bit_flipped_tail() -> Result<(), ToyKVError> {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let mut db = toykv::open(tmp_dir.path());
+
+    db.set(b"k1".to_vec(), b"v1".to_vec())?;
+    db.set(b"k2".to_vec(), b"v2".to_vec())?;
+    db.shutdown();
+
+    let wal_path = tmp_dir.path().join("db.wal.000001");
+    let full_len = std::fs::metadata(&wal_path).unwrap().len();
+    // Flip a bit inside the last record's value, leaving its length
+    // intact so this isn't just caught as a short read.
+    let mut f = OpenOptions::new().write(true).open(&wal_path).unwrap();
+    f.seek(SeekFrom::Start(full_len - 1)).unwrap();
+    f.write_all(&[0xFF]).unwrap();
+
+    let mut db = toykv::open(tmp_dir.path());
+    assert_eq!(db.get(b"k1")?.unwrap(), b"v1");
+    assert_eq!(db.get(b"k2")?, None, "corrupted record should not survive replay");
+    db.shutdown();
+
+    Ok(())
+}
+
+/// A bit-flip in the tail record's *magic* byte must be caught the same
+/// way as any other corrupt tail -- replay stops and keeps what came
+/// before it -- rather than panicking the whole process.
+#[test]
+fn recovers_from_bit_flipped_tail_magic_byte() -> Result<(), ToyKVError> {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let mut db = toykv::open(tmp_dir.path());
+
+    db.set(b"k1".to_vec(), b"v1".to_vec())?;
+    let before_k2_len = std::fs::metadata(tmp_dir.path().join("db.wal.000001"))
+        .unwrap()
+        .len();
+    db.set(b"k2".to_vec(), b"v2".to_vec())?;
+    db.shutdown();
+
+    // Flip the very first byte of k2's header -- its magic byte.
+    let wal_path = tmp_dir.path().join("db.wal.000001");
+    let mut f = OpenOptions::new().write(true).open(&wal_path).unwrap();
+    f.seek(SeekFrom::Start(before_k2_len)).unwrap();
+    f.write_all(&[0xFF]).unwrap();
+
+    let mut db = toykv::open(tmp_dir.path());
+    assert_eq!(db.get(b"k1")?.unwrap(), b"v1");
+    assert_eq!(db.get(b"k2")?, None, "corrupted record should not survive replay");
+    db.shutdown();
+
+    Ok(())
+}
+
+/// A bit-flip in the tail record's *version* byte must also be caught
+/// by the checksum (which covers the magic and version bytes along
+/// with the rest of the header) and treated as ordinary tail
+/// corruption, rather than escalated to an `UnsupportedVersion` error
+/// that would fail the whole open.
+#[test]
+fn recovers_from_bit_flipped_tail_version_byte() -> Result<(), ToyKVError> {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let mut db = toykv::open(tmp_dir.path());
+
+    db.set(b"k1".to_vec(), b"v1".to_vec())?;
+    let before_k2_len = std::fs::metadata(tmp_dir.path().join("db.wal.000001"))
+        .unwrap()
+        .len();
+    db.set(b"k2".to_vec(), b"v2".to_vec())?;
+    db.shutdown();
+
+    // Flip the second byte of k2's header -- its format version.
+    let wal_path = tmp_dir.path().join("db.wal.000001");
+    let mut f = OpenOptions::new().write(true).open(&wal_path).unwrap();
+    f.seek(SeekFrom::Start(before_k2_len + 1)).unwrap();
+    f.write_all(&[0xFF]).unwrap();
+
+    let mut db = toykv::open(tmp_dir.path());
+    assert_eq!(db.get(b"k1")?.unwrap(), b"v1");
+    assert_eq!(db.get(b"k2")?, None, "corrupted record should not survive replay");
+    db.shutdown();
+
+    Ok(())
+}
+
+/// Values bigger than a single WAL block get fragmented across several
+/// blocks on write and must be transparently reassembled on replay.
+#[test]
+fn large_value_fragments_across_blocks() -> Result<(), ToyKVError> {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let mut db = toykv::open(tmp_dir.path());
+
+    // A few times the 32 KiB block size, so this needs several blocks.
+    let big = vec![0xAB; 100 * 1024];
+    db.set(b"big".to_vec(), big.clone())?;
+    db.set(b"small".to_vec(), b"tiny".to_vec())?;
+    db.shutdown();
+
+    let mut db = toykv::open(tmp_dir.path());
+    assert_eq!(db.get(b"big")?.unwrap(), big);
+    assert_eq!(db.get(b"small")?.unwrap(), b"tiny");
+    db.shutdown();
+
+    Ok(())
+}
+
+/// Writing enough data to cross the segment size threshold should roll
+/// the WAL into a new numbered segment file rather than growing a
+/// single one forever, and replay should stitch the segments back
+/// together transparently.
+#[test]
+fn rotates_to_a_new_segment_past_the_size_threshold() -> Result<(), ToyKVError> {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let mut db = toykv::open(tmp_dir.path());
+
+    // Comfortably past the 4 MiB segment threshold, spread across many
+    // small-ish writes so several of them have to land after a roll.
+    let val = vec![0xCDu8; 64 * 1024];
+    for i in 0..80u32 {
+        db.set(format!("k{i}").into_bytes(), val.clone())?;
+    }
+    db.shutdown();
+
+    assert!(
+        tmp_dir.path().join("db.wal.000002").exists(),
+        "expected a second WAL segment to have been created"
+    );
+
+    let mut db = toykv::open(tmp_dir.path());
+    for i in 0..80u32 {
+        assert_eq!(db.get(format!("k{i}").as_bytes())?.unwrap(), val);
+    }
+    db.shutdown();
+
+    Ok(())
+}
+
+/// An atomic commit's queued writes all land together, and a failed
+/// `check` aborts every queued write in the batch -- not just the key
+/// it checked.
+#[test]
+fn atomic_commit_applies_all_or_nothing() -> Result<(), ToyKVError> {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let mut db = toykv::open(tmp_dir.path());
+
+    db.set(b"a".to_vec(), b"1".to_vec())?;
+
+    let applied = db
+        .atomic()
+        .check(b"a", 0)
+        .set(b"b".to_vec(), b"2".to_vec())
+        .set(b"c".to_vec(), b"3".to_vec())
+        .commit()?;
+    assert!(applied, "check against a's real seq should pass");
+    assert_eq!(db.get(b"b")?.unwrap(), b"2");
+    assert_eq!(db.get(b"c")?.unwrap(), b"3");
+
+    // "a" was last written at seq 0, so checking against the wrong seq
+    // must fail the whole batch -- neither "d" nor "e" should appear,
+    // not even the one that, queued alone, would have succeeded.
+    let applied = db
+        .atomic()
+        .check(b"a", 999)
+        .set(b"d".to_vec(), b"4".to_vec())
+        .set(b"e".to_vec(), b"5".to_vec())
+        .commit()?;
+    assert!(!applied, "check against the wrong seq should fail the batch");
+    assert_eq!(db.get(b"d")?, None);
+    assert_eq!(db.get(b"e")?, None);
+
+    db.shutdown();
+    Ok(())
+}
+
+/// A delete must tombstone a previously set key, with `get` returning
+/// `None` -- and that tombstone must survive a reopen/replay, not just
+/// stick around in the live memtable.
+#[test]
+fn delete_supersedes_earlier_set() -> Result<(), ToyKVError> {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let mut db = toykv::open(tmp_dir.path());
+
+    db.set(b"k1".to_vec(), b"v1".to_vec())?;
+    db.delete(b"k1".to_vec())?;
+    assert_eq!(db.get(b"k1")?, None);
+    db.shutdown();
+
+    let mut db = toykv::open(tmp_dir.path());
+    assert_eq!(db.get(b"k1")?, None, "tombstone should survive replay");
+    db.shutdown();
+
+    Ok(())
+}
+
+/// The IEEE CRC-32 used by the legacy, pre-chunk0-5 WAL block header,
+/// reimplemented here rather than reaching into the crate's private
+/// `wal::crc32` so this test only ever depends on the on-disk format
+/// described in `wal.rs`'s module docs, not the crate's internals.
+fn legacy_crc32(data: &[u8]) -> u32 {
+    const POLY: u32 = 0xEDB88320;
+    let mut crc = 0xFFFFFFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc ^ 0xFFFFFFFF
+}
+
+/// Hand-builds a single pre-chunk0-5 legacy block (15-byte header, no
+/// format-version byte) wrapping one key/value pair, in the format
+/// `wal::compat::read_legacy_block` expects.
+fn legacy_block(seq: u32, key: &[u8], value: &[u8]) -> Vec<u8> {
+    let mut kv = Vec::new();
+    kv.extend((key.len() as u16).to_be_bytes());
+    kv.extend((value.len() as u32).to_be_bytes());
+    kv.extend(0u32.to_be_bytes()); // pad
+    kv.extend(key);
+    kv.extend(value);
+
+    let op = 1u8; // OP_SET
+    let rtype = 0u8; // RecordType::Full
+    let batch_len = 1u16;
+
+    let mut checked = Vec::new();
+    checked.extend(seq.to_be_bytes());
+    checked.push(op);
+    checked.push(rtype);
+    checked.extend(batch_len.to_be_bytes());
+    checked.extend(&kv);
+    let crc = legacy_crc32(&checked);
+
+    let mut block = Vec::new();
+    block.push(b'w'); // WAL_MAGIC
+    block.extend(seq.to_be_bytes());
+    block.push(op);
+    block.push(rtype);
+    block.extend(batch_len.to_be_bytes());
+    block.extend(crc.to_be_bytes());
+    block.extend((kv.len() as u16).to_be_bytes());
+    block.extend(kv);
+    block
+}
+
+/// `toykv::upgrade` should migrate a legacy `db.wal` into segment 1 in
+/// the current format, preserving its records, and leave the directory
+/// in a state the normal open/replay path can read.
+#[test]
+fn upgrade_migrates_legacy_wal_round_trip() -> Result<(), ToyKVError> {
+    let tmp_dir = tempfile::tempdir().unwrap();
+
+    let mut legacy = Vec::new();
+    legacy.extend(legacy_block(0, b"k1", b"v1"));
+    legacy.extend(legacy_block(1, b"k2", b"v2"));
+    std::fs::write(tmp_dir.path().join("db.wal"), &legacy).unwrap();
+
+    toykv::upgrade(tmp_dir.path())?;
+    assert!(
+        !tmp_dir.path().join("db.wal").exists(),
+        "legacy WAL should be removed once migrated"
+    );
+    assert!(tmp_dir.path().join("db.wal.000001").exists());
+
+    let mut db = toykv::open(tmp_dir.path());
+    assert_eq!(db.get(b"k1")?.unwrap(), b"v1");
+    assert_eq!(db.get(b"k2")?.unwrap(), b"v2");
+    db.shutdown();
+
+    Ok(())
+}
+
+// No test here exercises `WAL::peel` or `WALSync::Batched` directly:
+// both need a public entry point this snapshot doesn't have -- a way
+// to open with a chosen `WALSync`, and the (currently absent) flush
+// caller that would ever invoke `peel` -- so there's nothing an
+// external, public-API-only test like this one can drive.